@@ -23,11 +23,18 @@
 
 extern crate parking_lot;
 extern crate coco;
-use std::mem::drop;
+#[cfg(feature = "async")]
+extern crate tokio;
+use std::mem::{drop, ManuallyDrop};
+use std::ptr;
 use std::sync::Arc;
 use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
 use coco::epoch::{self, Atomic, Garbage};
-use parking_lot::{Mutex, MutexGuard};
+use parking_lot::{ArcMutexGuard, Mutex, MutexGuard, RawMutex};
+
+mod replicated;
+pub use replicated::ReplicatedRcuLock;
 
 #[derive(Debug)]
 pub struct RcuLock<T> {
@@ -38,7 +45,9 @@ pub struct RcuLock<T> {
     /// when multiple writers each acquire a copy of the resource protected by the
     /// `RcuLock`, write to it, and then store their individual changes to the master `RcuLock`.
     /// Acquired on `write()` and released when `RcuGuard` is dropped.
-    write_lock: Mutex<()>,
+    /// Wrapped in an `Arc` so `write_owned` can hand out a `'static`
+    /// guard via `parking_lot`'s `lock_arc`.
+    write_lock: Arc<Mutex<()>>,
     /// Epoch-based garbage collector to free our Arc<T> once there's no more
     /// references to it.
     garbage: Garbage,
@@ -50,7 +59,7 @@ impl<T: Clone + Send + 'static> RcuLock<T> {
         let inner = Atomic::from_box(Box::new(Arc::new(target)), 0);
         RcuLock {
             inner: inner,
-            write_lock: Mutex::new(()),
+            write_lock: Arc::new(Mutex::new(())),
             garbage: Garbage::new(),
         }
     }
@@ -67,6 +76,13 @@ impl<T: Clone + Send + 'static> RcuLock<T> {
     /// the `RcuLock` has already handed one out to another writer.
     ///
     /// Clones the data protected by the `RcuLock`, which can be expensive.
+    ///
+    /// # Warning
+    ///
+    /// Don't mix calls to `write` (or `try_write`/`try_write_for`/
+    /// `try_write_until`/`write_async`, which share `write_lock`) with calls
+    /// to `update` on the same `RcuLock`; see the warning on `update` for
+    /// why.
     pub fn write(&self) -> RcuGuard<T> {
         let guard = self.write_lock.lock();
         let data = epoch::pin(|pin| {
@@ -74,10 +90,176 @@ impl<T: Clone + Send + 'static> RcuLock<T> {
         });
         RcuGuard {
             lock: self,
-            data: data,
+            data: Box::new(data),
             _guard: guard,
         }
     }
+
+    /// Attempts to acquire an exclusive write handle without blocking.
+    ///
+    /// Returns `None` immediately if another `RcuGuard` is currently alive.
+    /// The data is only cloned once `write_lock` is actually held, so a
+    /// failed attempt never pays the `T::clone` cost.
+    pub fn try_write(&self) -> Option<RcuGuard<'_, T>> {
+        let guard = self.write_lock.try_lock()?;
+        let data = epoch::pin(|pin| {
+            T::clone(self.inner.load(pin).unwrap())
+        });
+        Some(RcuGuard {
+            lock: self,
+            data: Box::new(data),
+            _guard: guard,
+        })
+    }
+
+    /// Like `try_write`, but waits up to `timeout` for `write_lock` to
+    /// become available before giving up.
+    pub fn try_write_for(&self, timeout: Duration) -> Option<RcuGuard<'_, T>> {
+        let guard = self.write_lock.try_lock_for(timeout)?;
+        let data = epoch::pin(|pin| {
+            T::clone(self.inner.load(pin).unwrap())
+        });
+        Some(RcuGuard {
+            lock: self,
+            data: Box::new(data),
+            _guard: guard,
+        })
+    }
+
+    /// Like `try_write`, but waits until `deadline` for `write_lock` to
+    /// become available before giving up.
+    pub fn try_write_until(&self, deadline: Instant) -> Option<RcuGuard<'_, T>> {
+        let guard = self.write_lock.try_lock_until(deadline)?;
+        let data = epoch::pin(|pin| {
+            T::clone(self.inner.load(pin).unwrap())
+        });
+        Some(RcuGuard {
+            lock: self,
+            data: Box::new(data),
+            _guard: guard,
+        })
+    }
+
+    /// Asynchronously acquire an exclusive write handle, returning the same
+    /// `RcuGuard` that `write`/`try_write` use, so it serializes against
+    /// them on the very same `write_lock` rather than racing with them.
+    ///
+    /// Acquiring `write_lock` still blocks the worker thread the calling
+    /// task runs on: the wait happens inside `tokio::task::block_in_place`,
+    /// which first moves the other tasks queued on that worker to another
+    /// thread so they keep running. The calling task itself does not yield
+    /// to the runtime while it waits. Requires the `async` feature
+    /// and a multi-threaded Tokio runtime (`block_in_place` panics on a
+    /// current-thread runtime).
+    #[cfg(feature = "async")]
+    pub async fn write_async(&self) -> RcuGuard<T> {
+        let guard = tokio::task::block_in_place(|| self.write_lock.lock());
+        let data = epoch::pin(|pin| {
+            T::clone(self.inner.load(pin).unwrap())
+        });
+        RcuGuard {
+            lock: self,
+            data: Box::new(data),
+            _guard: guard,
+        }
+    }
+
+    /// Acquire an owned read handle. Since `read` already hands back a
+    /// `'static` `Arc<T>` that doesn't borrow from the `RcuLock`, this is
+    /// just `read` under a name that mirrors `write_owned`; it takes `&self`
+    /// rather than `self: &Arc<Self>` since it has no actual need of the
+    /// `Arc<RcuLock<T>>` itself.
+    pub fn read_owned(&self) -> Arc<T> {
+        self.read()
+    }
+
+    /// Apply `f` to the current value and atomically install the result,
+    /// without ever taking `write_lock`.
+    ///
+    /// This runs the classic RCU update loop: load the current `Arc<T>`,
+    /// compute a candidate replacement by calling `f` on it, then attempt
+    /// `cas_box` against the atomic pointer. If another writer (via
+    /// `update`, `write`, or `try_write`) swapped in a new value first, the
+    /// candidate is stale (it was computed from data that's no longer
+    /// current), so it's recomputed against the fresh value `cas_box`
+    /// handed back and the loop retries, reusing that returned `Box`
+    /// instead of allocating a new one each attempt.
+    ///
+    /// Because of retries, `f` may run more than once per call to `update`,
+    /// so it must be pure and free of side effects. Writers that touch
+    /// disjoint state can make progress concurrently through this path
+    /// instead of serializing on `write_lock`; callers that want coarse,
+    /// single-writer semantics should keep using `write`.
+    ///
+    /// # Warning
+    ///
+    /// `update` never takes `write_lock`, while `write`/`try_write`/
+    /// `write_async` store back with an unconditional `swap_box` rather than
+    /// a CAS. Calling `update` concurrently with any of those on the same
+    /// `RcuLock` is a lost-update hazard: `update` can install its result
+    /// after a `write`-family guard has cloned its snapshot, and when that
+    /// guard is dropped its `swap_box` overwrites `update`'s result without
+    /// ever seeing it happened. Pick one style of writer per
+    /// `RcuLock` instance and stick to it.
+    pub fn update<F>(&self, mut f: F)
+    where
+        F: FnMut(&T) -> T,
+    {
+        epoch::pin(|pin| {
+            let mut current = self.inner.load(pin);
+            let mut new_value = Box::new(Arc::new(f(current.unwrap())));
+            loop {
+                match self.inner.cas_box(current, new_value, 0) {
+                    Ok(_) => {
+                        // `current` is the pointer that was just replaced,
+                        // i.e. the real old value; it's safe to reclaim now
+                        // that the atomic points at `new_value` instead.
+                        unsafe {
+                            self.garbage.defer_drop(current.as_raw(), 1, pin);
+                        }
+                        break;
+                    }
+                    Err((actual, mut returned_box)) => {
+                        current = actual;
+                        *returned_box = Arc::new(f(current.unwrap()));
+                        new_value = returned_box;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Acquire an exclusive write handle that owns an `Arc<RcuLock<T>>`
+    /// instead of borrowing it, so the returned `OwnedRcuGuard` is `'static`
+    /// and can be stored in a `'static` struct, or acquired inside a spawned
+    /// thread that owns a clone of the `Arc`. The guard is not `Send` (see
+    /// `OwnedRcuGuard`), so it cannot be moved to another thread once
+    /// acquired. Blocks if another `RcuGuard`/`OwnedRcuGuard` is alive.
+    pub fn write_owned(self: Arc<Self>) -> OwnedRcuGuard<T> {
+        let guard = self.write_lock.lock_arc();
+        let data = epoch::pin(|pin| {
+            T::clone(self.inner.load(pin).unwrap())
+        });
+        OwnedRcuGuard {
+            lock: self,
+            data,
+            _guard: guard,
+        }
+    }
+
+    /// Atomically install `data` as the new current value and schedule the
+    /// replaced value for epoch-based reclamation. Shared by every guard's
+    /// `Drop` impl (`RcuGuard`, `MappedRcuGuard`, `OwnedRcuGuard`) so the
+    /// store-back logic lives in one place.
+    fn store_back(&self, data: T) {
+        let data = Box::new(Arc::new(data));
+        epoch::pin(|pin| {
+            let old_data = self.inner.swap_box(data, 0, pin);
+            unsafe {
+                self.garbage.defer_drop(old_data.as_raw(), 1, pin);
+            }
+        });
+    }
 }
 
 impl<T> Drop for RcuLock<T> {
@@ -91,10 +273,35 @@ impl<T> Drop for RcuLock<T> {
 
 pub struct RcuGuard<'a, T: Clone + Send + 'static> {
     lock: &'a RcuLock<T>,
-    data: T,
+    /// Boxed so its address stays stable across a move, which `map` relies
+    /// on to hand out a raw pointer into it.
+    data: Box<T>,
     _guard: MutexGuard<'a, ()>,
 }
 
+impl<'a, T: Clone + Send + 'static> RcuGuard<'a, T> {
+    /// Projects this guard onto a sub-field of `T`, returning a
+    /// `MappedRcuGuard` that derefs to `&mut U` while still performing the
+    /// full store-back of `T` when it is dropped. Useful when `T` is a
+    /// large struct and a caller only needs scoped access to one field.
+    pub fn map<U, F>(mut guard: RcuGuard<'a, T>, f: F) -> MappedRcuGuard<'a, T, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        let mapped: *mut U = f(&mut guard.data);
+        // `RcuGuard` has a `Drop` impl, so its fields can't be moved out of
+        // normally; go through `ManuallyDrop` to lift them into the mapped
+        // guard without running `RcuGuard::drop`.
+        let guard = ManuallyDrop::new(guard);
+        MappedRcuGuard {
+            lock: guard.lock,
+            data: unsafe { ptr::read(&guard.data) },
+            mapped,
+            _guard: unsafe { ptr::read(&guard._guard) },
+        }
+    }
+}
+
 impl<'a, T: Clone + Send + 'static> DerefMut for RcuGuard<'a, T> {
     fn deref_mut(&mut self) -> &mut T {
         &mut self.data
@@ -111,12 +318,67 @@ impl<'a, T: Clone + Send + 'static> Deref for RcuGuard<'a, T> {
 /// On drop, atomically store the data back into the owning `RcuLock`.
 impl<'a, T: Clone + Send + 'static> Drop for RcuGuard<'a, T> {
     fn drop(&mut self) {
-        let data = Box::new(Arc::new(self.data.clone()));
-        epoch::pin(|pin| {
-            let old_data = self.lock.inner.swap_box(data, 0, pin);
-            unsafe {
-                self.lock.garbage.defer_drop(old_data.as_raw(), 1, pin);
-            }
-        });
+        self.lock.store_back((*self.data).clone());
+    }
+}
+
+/// A guard produced by `RcuGuard::map`, giving scoped `&mut U` access to a
+/// sub-field of `T` while still storing the whole `T` back into the
+/// `RcuLock` on drop.
+pub struct MappedRcuGuard<'a, T: Clone + Send + 'static, U: ?Sized> {
+    lock: &'a RcuLock<T>,
+    data: Box<T>,
+    mapped: *mut U,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl<'a, T: Clone + Send + 'static, U: ?Sized> DerefMut for MappedRcuGuard<'a, T, U> {
+    fn deref_mut(&mut self) -> &mut U {
+        unsafe { &mut *self.mapped }
+    }
+}
+
+impl<'a, T: Clone + Send + 'static, U: ?Sized> Deref for MappedRcuGuard<'a, T, U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe { &*self.mapped }
+    }
+}
+
+/// On drop, atomically store the (possibly mutated) full `T` back into the
+/// owning `RcuLock`, same as `RcuGuard`.
+impl<'a, T: Clone + Send + 'static, U: ?Sized> Drop for MappedRcuGuard<'a, T, U> {
+    fn drop(&mut self) {
+        self.lock.store_back((*self.data).clone());
+    }
+}
+
+/// An owned version of `RcuGuard` that holds an `Arc<RcuLock<T>>` rather
+/// than borrowing the `RcuLock`, so it is `'static`. It is not `Send`: the
+/// `parking_lot` `ArcMutexGuard` it holds is not `Send` with only the
+/// `arc_lock` feature enabled. Obtained from `RcuLock::write_owned`.
+pub struct OwnedRcuGuard<T: Clone + Send + 'static> {
+    lock: Arc<RcuLock<T>>,
+    data: T,
+    _guard: ArcMutexGuard<RawMutex, ()>,
+}
+
+impl<T: Clone + Send + 'static> DerefMut for OwnedRcuGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+impl<T: Clone + Send + 'static> Deref for OwnedRcuGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+/// On drop, atomically store the data back into the owning `RcuLock`.
+impl<T: Clone + Send + 'static> Drop for OwnedRcuGuard<T> {
+    fn drop(&mut self) {
+        self.lock.store_back(self.data.clone());
     }
 }
@@ -0,0 +1,99 @@
+//! Per-thread replicated reads backed by an append-only operation log, in
+//! the style of a node-replication (NR) scheme: instead of cloning the full
+//! `T` on every write like `RcuLock` does, writers append small `Op`s to a
+//! shared log, and each replica catches up by replaying the ops it hasn't
+//! seen yet. Readers on different cores then touch different replica
+//! copies and rarely contend, and the cost of `T::clone` is only paid once,
+//! at construction.
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::thread;
+
+struct Replica<T> {
+    data: T,
+    /// Number of log entries already applied to `data`.
+    applied: usize,
+}
+
+/// An opt-in alternative to `RcuLock` for workloads with a large `T`, where
+/// handing out an `Arc` to a single shared copy on every read is too
+/// contended. The user supplies `apply`, which folds an `Op` into a replica
+/// in place; writers push `Op`s onto a shared, monotonically-indexed log,
+/// and readers replay pending ops into their own replica before reading it.
+///
+/// # Examples
+/// ```
+/// use rculock::ReplicatedRcuLock;
+///
+/// let lock = ReplicatedRcuLock::new(0i32, 4, |data: &mut i32, op: &i32| *data += op);
+/// lock.write(5);
+/// lock.write(2);
+/// assert_eq!(7, lock.read(|data| *data));
+/// ```
+pub struct ReplicatedRcuLock<T: Clone, Op> {
+    apply: fn(&mut T, &Op),
+    /// Shared, append-only log of operations, in commit order.
+    log: RwLock<Vec<Op>>,
+    /// One independently-locked replica per slot; readers are hashed onto
+    /// a slot by thread id so concurrent readers on different cores usually
+    /// land on different replicas.
+    replicas: Vec<Mutex<Replica<T>>>,
+}
+
+impl<T: Clone, Op> ReplicatedRcuLock<T, Op> {
+    /// Create a new `ReplicatedRcuLock` with `num_replicas` independent
+    /// copies of `target`, each brought up to date by replaying the shared
+    /// op log via `apply`.
+    pub fn new(target: T, num_replicas: usize, apply: fn(&mut T, &Op)) -> ReplicatedRcuLock<T, Op> {
+        assert!(num_replicas > 0, "ReplicatedRcuLock needs at least one replica");
+        let replicas = (0..num_replicas)
+            .map(|_| {
+                Mutex::new(Replica {
+                    data: target.clone(),
+                    applied: 0,
+                })
+            })
+            .collect();
+        ReplicatedRcuLock {
+            apply,
+            log: RwLock::new(Vec::new()),
+            replicas,
+        }
+    }
+
+    /// Append `op` to the shared log. This never touches a replica
+    /// directly; `op` is lazily folded in the next time each replica is
+    /// read.
+    pub fn write(&self, op: Op) {
+        self.log.write().push(op);
+    }
+
+    /// Sync this thread's replica up to the current log tail, then run `f`
+    /// against it. Ops are applied in log order exactly once per replica,
+    /// so the replica `f` sees is always at least as new as the latest
+    /// write committed before this call.
+    pub fn read<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        let mut replica = self.replicas[self.replica_index()].lock();
+        self.catch_up(&mut replica);
+        f(&replica.data)
+    }
+
+    /// Replay any log entries not yet applied to `replica`.
+    fn catch_up(&self, replica: &mut Replica<T>) {
+        let log = self.log.read();
+        for op in &log[replica.applied..] {
+            (self.apply)(&mut replica.data, op);
+        }
+        replica.applied = log.len();
+    }
+
+    /// Hash the calling thread's id onto a replica slot, so repeated reads
+    /// from the same thread consistently land on the same replica.
+    fn replica_index(&self) -> usize {
+        let mut hasher = DefaultHasher::new();
+        thread::current().id().hash(&mut hasher);
+        (hasher.finish() as usize) % self.replicas.len()
+    }
+}
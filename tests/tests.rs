@@ -1,6 +1,15 @@
 extern crate rculock;
+#[cfg(feature = "async")]
+extern crate tokio;
 use std::sync::Arc;
-use rculock::{RcuLock, RcuGuard};
+use std::time::Duration;
+use rculock::{RcuLock, RcuGuard, ReplicatedRcuLock};
+
+#[derive(Clone)]
+struct Pair {
+    first: u32,
+    second: u32,
+}
 
 #[test]
 fn test() {
@@ -79,6 +88,111 @@ fn hashmap() {
     assert_eq!(Some(&999), res);
 }
 
+#[test]
+fn try_write_contention() {
+    use std::thread;
+    let data = Arc::new(RcuLock::new(5));
+    let guard = data.write();
+    assert!(data.try_write().is_none());
+    assert!(data.try_write_for(Duration::from_millis(10)).is_none());
+
+    let data2 = data.clone();
+    let t = thread::spawn(move || {
+        assert!(data2.try_write_for(Duration::from_millis(500)).is_some());
+    });
+    thread::sleep_ms(50);
+    drop(guard);
+    t.join().unwrap();
+}
+
+#[test]
+fn write_owned_crosses_thread() {
+    use std::thread;
+    let data = Arc::new(RcuLock::new(5));
+    let t = {
+        let data = data.clone();
+        thread::spawn(move || {
+            let mut guard = data.write_owned();
+            *guard = 4;
+        })
+    };
+    t.join().unwrap();
+    assert_eq!(4, *data.read());
+}
+
+#[test]
+fn update_concurrent_increments() {
+    use std::thread;
+    let data = Arc::new(RcuLock::new(0));
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let data = data.clone();
+            thread::spawn(move || {
+                for _ in 0..250 {
+                    data.update(|x| x + 1);
+                }
+            })
+        })
+        .collect();
+
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    assert_eq!(1000, *data.read());
+}
+
+#[test]
+fn mapped_guard_writes_back_whole_struct() {
+    let data = RcuLock::new(Pair { first: 1, second: 2 });
+    {
+        let guard = data.write();
+        let mut mapped = RcuGuard::map(guard, |pair| &mut pair.second);
+        *mapped = 20;
+    }
+    let result = data.read();
+    assert_eq!(1, result.first);
+    assert_eq!(20, result.second);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test(flavor = "multi_thread")]
+async fn write_async_stores_back_on_drop() {
+    let data = RcuLock::new(5);
+    {
+        let mut guard = data.write_async().await;
+        *guard = 4;
+        assert_eq!(5, *data.read());
+    }
+    assert_eq!(4, *data.read());
+}
+
+#[test]
+fn replicated_reads_apply_ops_in_order() {
+    use std::thread;
+    let lock = Arc::new(ReplicatedRcuLock::new(
+        Vec::<i32>::new(),
+        4,
+        |data: &mut Vec<i32>, op: &i32| data.push(*op),
+    ));
+
+    for i in 0..100 {
+        lock.write(i);
+    }
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let lock = lock.clone();
+            thread::spawn(move || lock.read(|data| data.clone()))
+        })
+        .collect();
+
+    for t in threads {
+        let replica = t.join().unwrap();
+        assert_eq!((0..100).collect::<Vec<i32>>(), replica);
+    }
+}
+
 #[test]
 fn hashmap_race_condition() {
     use std::thread;